@@ -3,19 +3,36 @@ use errors::DenoError;
 use errors::DenoResult;
 use errors::ErrorKind;
 use fs as deno_fs;
+use import_map::ImportMap;
+use lockfile::Lockfile;
 use net;
+use net::FetchResult;
 use ring;
+use serde_json;
 use std;
 use std::fmt::Write;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use std::result::Result;
+use std::sync::Mutex;
 #[cfg(test)]
 use tempfile::TempDir;
 use url;
 use url::Url;
 
+// How a remote module should be fetched relative to the local cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+  // Use the cached copy if one exists; otherwise download it.
+  UseCache,
+  // Always revalidate (or re-download, if uncached) against the network.
+  Reload,
+  // Never touch the network. A remote module missing from the cache is an
+  // error rather than a download, enabling reproducible/air-gapped builds.
+  CachedOnly,
+}
+
 pub struct DenoDir {
   // Example: /Users/rld/.deno/
   pub root: PathBuf,
@@ -27,16 +44,30 @@ pub struct DenoDir {
   // This is where we cache compilation outputs. Example:
   // /Users/rld/.deno/gen/f39a473452321cacd7c346a870efb0e3e1264b43.js
   pub deps: PathBuf,
-  // If remote resources should be reloaded.
-  reload: bool,
+  // How remote modules should be fetched relative to the local cache. See
+  // CachePolicy.
+  cache_policy: CachePolicy,
+  // If true, newly-seen remote modules are recorded into the lockfile. If
+  // false, the lockfile is read-only and enforcing: an unseen remote module
+  // is treated as an integrity failure rather than silently trusted.
+  lock_write: bool,
+  // Maps remote module name -> "sha256-<base64>" digest. Persisted at
+  // `root.join("deno.lock")`. See lockfile::Lockfile.
+  lockfile: Mutex<Lockfile>,
+  // Optional import map used to rewrite bare specifiers (e.g. "lodash")
+  // before the normal local/remote resolution logic runs. See
+  // import_map::ImportMap.
+  import_map: Option<ImportMap>,
 }
 
 impl DenoDir {
   // Must be called before using any function from this module.
   // https://github.com/denoland/deno/blob/golang/deno_dir.go#L99-L111
   pub fn new(
-    reload: bool,
+    cache_policy: CachePolicy,
     custom_root: Option<&Path>,
+    lock_write: bool,
+    import_map_path: Option<&Path>,
   ) -> std::io::Result<DenoDir> {
     // Only setup once.
     let home_dir = std::env::home_dir().expect("Could not get home directory.");
@@ -48,12 +79,25 @@ impl DenoDir {
     };
     let gen = root.as_path().join("gen");
     let deps = root.as_path().join("deps");
+    let lockfile = Mutex::new(Lockfile::new(root.join("deno.lock"))?);
+    let import_map = match import_map_path {
+      Some(path) => {
+        let contents = fs::read_to_string(path)?;
+        Some(ImportMap::from_json(&contents).map_err(|e| {
+          std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+        })?)
+      }
+      None => None,
+    };
 
     let deno_dir = DenoDir {
       root,
       gen,
       deps,
-      reload,
+      cache_policy,
+      lock_write,
+      lockfile,
+      import_map,
     };
     deno_fs::mkdir(deno_dir.gen.as_ref())?;
     deno_fs::mkdir(deno_dir.deps.as_ref())?;
@@ -72,7 +116,12 @@ impl DenoDir {
     source_code: &str,
   ) -> PathBuf {
     let cache_key = source_code_hash(filename, source_code);
-    self.gen.join(cache_key + ".js")
+    // Prefixed with the cache-key scheme version, so a hash-algorithm
+    // upgrade (like SHA-1 -> SHA-256) produces a disjoint set of filenames
+    // instead of silently mis-reading entries generated by the old scheme.
+    self
+      .gen
+      .join(format!("{}_{}.js", CACHE_KEY_VERSION, cache_key))
   }
 
   fn load_cache(
@@ -92,39 +141,185 @@ impl DenoDir {
     output_code: &str,
   ) -> std::io::Result<()> {
     let cache_path = self.cache_path(filename, source_code);
-    // TODO(ry) This is a race condition w.r.t to exists() -- probably should
-    // create the file in exclusive mode. A worry is what might happen is there
-    // are two processes and one reads the cache file while the other is in the
-    // midst of writing it.
     if cache_path.exists() {
-      Ok(())
-    } else {
-      fs::write(cache_path, output_code.as_bytes())
+      return Ok(());
     }
+    // Write to a uniquely-named temp file in `gen` first, then rename into
+    // place. `fs::rename` is atomic on the same filesystem, so a reader in
+    // load_cache never observes a half-written file, unlike a direct write
+    // to `cache_path`.
+    let temp_path = self.gen.join(format!(
+      "{}.{}.tmp",
+      cache_path.file_name().unwrap().to_str().unwrap(),
+      std::process::id()
+    ));
+    fs::write(&temp_path, output_code.as_bytes())?;
+    fs::rename(&temp_path, &cache_path)
   }
 
   // Prototype https://github.com/denoland/deno/blob/golang/deno_dir.go#L37-L73
+  //
+  // `module_name`/`filename` are the *requested* identity (pre-redirect).
+  // Returns the resolved identity (post-redirect, if the server redirected)
+  // along with the source text and Content-Type, if known. On a cache hit
+  // that doesn't need revalidating (CachePolicy::UseCache or CachedOnly),
+  // the Content-Type comes from the sidecar metadata file written on the
+  // previous fetch.
   fn fetch_remote_source(
     self: &DenoDir,
     module_name: &str,
     filename: &str,
-  ) -> DenoResult<String> {
+  ) -> DenoResult<ResolvedSource> {
+    self.fetch_remote_source_with(
+      module_name,
+      filename,
+      net::fetch_sync_string_conditional,
+    )
+  }
+
+  // Same as fetch_remote_source, but takes the conditional-fetch call as a
+  // parameter so tests can exercise CachePolicy::Reload's revalidation
+  // logic with a fake `fetch` instead of hitting the network. See
+  // net::fetch_sync_string_conditional for the real one.
+  fn fetch_remote_source_with<F>(
+    self: &DenoDir,
+    module_name: &str,
+    filename: &str,
+    fetch: F,
+  ) -> DenoResult<ResolvedSource>
+  where
+    F: Fn(&str, Option<&str>, Option<&str>) -> DenoResult<FetchResult>,
+  {
     let p = Path::new(filename);
+    let cached_headers = CachedHeaders::load(filename);
+
+    if self.cache_policy == CachePolicy::CachedOnly && !p.exists() {
+      return Err(DenoError::new(
+        ErrorKind::CachedOnlyNotFound,
+        format!(
+          "Cannot find remote module \"{}\" in the cache: module not found \
+           in cache, run without --cached-only to download",
+          module_name
+        ),
+      ));
+    }
 
-    let src = if self.reload || !p.exists() {
+    let (src, fetched) = if !p.exists() {
       println!("Downloading {}", module_name);
-      let source = net::fetch_sync_string(module_name)?;
-      match p.parent() {
-        Some(ref parent) => fs::create_dir_all(parent),
-        None => Ok(()),
-      }?;
-      deno_fs::write_file_sync(&p, source.as_bytes())?;
-      source
+      let fetched = match fetch(module_name, None, None)? {
+        FetchResult::Fresh(fetched) => fetched,
+        // No conditional headers were sent, so a well-behaved server
+        // should never answer 304 here -- but the server isn't trusted
+        // input, so treat a misbehaving one as a recoverable error.
+        FetchResult::NotModified => {
+          return Err(DenoError::new(
+            ErrorKind::Other,
+            format!(
+              "{} returned 304 Not Modified to a request with no \
+               conditional headers",
+              module_name
+            ),
+          ))
+        }
+      };
+      (fetched.body.clone(), Some(fetched))
+    } else if self.cache_policy == CachePolicy::Reload {
+      println!("Downloading {}", module_name);
+      let etag = cached_headers.as_ref().and_then(|h| h.etag.clone());
+      let last_modified =
+        cached_headers.as_ref().and_then(|h| h.last_modified.clone());
+      match fetch(
+        module_name,
+        etag.as_ref().map(String::as_str),
+        last_modified.as_ref().map(String::as_str),
+      )? {
+        FetchResult::NotModified => {
+          let source = fs::read_to_string(&p)?;
+          (source, None)
+        }
+        FetchResult::Fresh(fetched) => (fetched.body.clone(), Some(fetched)),
+      }
     } else {
       let source = fs::read_to_string(&p)?;
-      source
+      (source, None)
+    };
+
+    // If the server redirected, the body belongs under the *final* URL's
+    // cache path, not the requested one; figure out where that is, but
+    // don't touch disk yet -- nothing gets persisted until it passes the
+    // integrity check below.
+    let (resolved_module_name, resolved_filename) = match &fetched {
+      Some(fetched) if fetched.final_url != module_name => {
+        let final_url = Url::parse(&fetched.final_url)?;
+        let final_filename = deno_fs::normalize_path(
+          get_cache_filename(self.deps.as_path(), final_url).as_ref(),
+        );
+        (fetched.final_url.clone(), final_filename)
+      }
+      _ => (module_name.to_string(), filename.to_string()),
+    };
+
+    // Verify before persisting: a tampered or mismatching fetch must never
+    // be written to the cache, since that would clobber a previously
+    // verified copy with unverified bytes even though this call errors out.
+    self.check_integrity(&resolved_module_name, src.as_bytes())?;
+
+    let content_type = match fetched {
+      Some(fetched) => {
+        let resolved_path = Path::new(&resolved_filename);
+        match resolved_path.parent() {
+          Some(ref parent) => fs::create_dir_all(parent),
+          None => Ok(()),
+        }?;
+        deno_fs::write_file_sync(resolved_path, src.as_bytes())?;
+
+        if resolved_filename != filename {
+          RedirectPointer {
+            to: resolved_module_name.clone(),
+          }.save(filename)?;
+        }
+
+        let content_type = fetched.content_type.clone();
+        CachedHeaders::from(fetched).save(&resolved_filename)?;
+        content_type
+      }
+      None => cached_headers.and_then(|h| h.content_type),
     };
-    Ok(src)
+
+    Ok(ResolvedSource {
+      module_name: resolved_module_name,
+      filename: resolved_filename,
+      source_code: src,
+      maybe_content_type: content_type,
+    })
+  }
+
+  // Verifies `bytes` against the lockfile entry for `module_name`, or
+  // records it if this is the first time `module_name` has been seen and
+  // `lock_write` is enabled. See lockfile::Lockfile.
+  fn check_integrity(
+    self: &DenoDir,
+    module_name: &str,
+    bytes: &[u8],
+  ) -> DenoResult<()> {
+    let mut lockfile = self.lockfile.lock().unwrap();
+    let result =
+      lockfile.check_or_insert(module_name, bytes, self.lock_write);
+    match result {
+      Ok(()) => {
+        if self.lock_write {
+          lockfile.write()?;
+        }
+        Ok(())
+      }
+      Err((expected, actual)) => Err(DenoError::new(
+        ErrorKind::IntegrityMismatch,
+        format!(
+          "Integrity check failed for \"{}\": expected {}, got {}",
+          module_name, expected, actual
+        ),
+      )),
+    }
   }
 
   // Prototype: https://github.com/denoland/deno/blob/golang/os.go#L122-L138
@@ -132,7 +327,7 @@ impl DenoDir {
     self: &DenoDir,
     module_name: &str,
     filename: &str,
-  ) -> DenoResult<String> {
+  ) -> DenoResult<ResolvedSource> {
     if is_remote(module_name) {
       self.fetch_remote_source(module_name, filename)
     } else if module_name.starts_with(ASSET_PREFIX) {
@@ -143,7 +338,12 @@ impl DenoDir {
         "if a module isn't remote, it should have the same filename"
       );
       let src = fs::read_to_string(Path::new(filename))?;
-      Ok(src)
+      Ok(ResolvedSource {
+        module_name: module_name.to_string(),
+        filename: filename.to_string(),
+        source_code: src,
+        maybe_content_type: None,
+      })
     }
   }
 
@@ -162,11 +362,12 @@ impl DenoDir {
 
     let result = self
       .get_source_code(module_name.as_str(), filename.as_str())
-      .and_then(|source_code| {
+      .and_then(|resolved| {
         Ok(CodeFetchOutput {
-          module_name,
-          filename,
-          source_code,
+          module_name: resolved.module_name,
+          filename: resolved.filename,
+          source_code: resolved.source_code,
+          maybe_content_type: resolved.maybe_content_type,
           maybe_output_code: None,
         })
       });
@@ -202,22 +403,36 @@ impl DenoDir {
         module_name: out.module_name,
         filename: out.filename,
         source_code: out.source_code,
+        maybe_content_type: out.maybe_content_type,
         maybe_output_code: Some(output_code),
       }),
     }
   }
 
   // Prototype: https://github.com/denoland/deno/blob/golang/os.go#L56-L68
+  //
+  // The inverse of get_cache_filename: a `deps` path's first component is
+  // the scheme ("http" or "https") get_cache_filename encoded it under.
+  // NOTE: this relies on the scheme segment added to the cache layout; a
+  // `deps` path left over from before that change will have its host in
+  // that position instead and won't round-trip correctly (see the note on
+  // get_cache_filename).
   fn src_file_to_url(self: &DenoDir, filename: &str) -> String {
     let filename_path = Path::new(filename);
     if filename_path.starts_with(&self.deps) {
       let rest = filename_path.strip_prefix(&self.deps).unwrap();
-      // Windows doesn't support ":" in filenames, so we represent port using a
-      // special string.
+      let mut components = rest.components();
+      let scheme = components
+        .next()
+        .and_then(|c| c.as_os_str().to_str())
+        .unwrap_or("http");
+      let rest = components.as_path().to_str().unwrap();
+      // Windows doesn't support ":" in filenames, so we represent port using
+      // a special string.
       // TODO(ry) This current implementation will break on a URL that has
       // the default port but contains "_PORT" in the path.
-      let rest = rest.to_str().unwrap().replacen("_PORT", ":", 1);
-      "http://".to_string() + &rest
+      let rest = rest.replacen("_PORT", ":", 1);
+      format!("{}://{}", scheme, rest)
     } else {
       String::from(filename)
     }
@@ -236,6 +451,17 @@ impl DenoDir {
     let module_specifier = self.src_file_to_url(module_specifier);
     let containing_file = self.src_file_to_url(containing_file);
 
+    // Give the import map, if any, a chance to rewrite a bare specifier
+    // (e.g. "lodash") into something the URL-join logic below can resolve.
+    // Specifiers the map doesn't know about -- relative paths, URLs,
+    // absolute paths -- fall through unchanged.
+    let module_specifier = match self.import_map {
+      Some(ref import_map) => import_map
+        .resolve(&module_specifier, &containing_file)
+        .unwrap_or(module_specifier),
+      None => module_specifier,
+    };
+
     debug!(
       "resolve_module module_specifier {} containing_file {}",
       module_specifier, containing_file
@@ -266,10 +492,35 @@ impl DenoDir {
         filename = p;
       }
       _ => {
-        module_name = j.to_string();
-        filename = deno_fs::normalize_path(
-          get_cache_filename(self.deps.as_path(), j).as_ref(),
-        )
+        // A previous fetch of this same URL may have been redirected
+        // elsewhere; chase the pointer file left at the requested path so
+        // we resolve straight to where the body actually lives, with no
+        // network hit.
+        let mut resolved_url = j;
+        let mut redirects_followed = 0;
+        loop {
+          let candidate_filename = deno_fs::normalize_path(
+            get_cache_filename(self.deps.as_path(), resolved_url.clone())
+              .as_ref(),
+          );
+          if redirects_followed >= MAX_REDIRECTS_CHASED {
+            module_name = resolved_url.to_string();
+            filename = candidate_filename;
+            break;
+          }
+          match RedirectPointer::load(&candidate_filename) {
+            Some(pointer) => {
+              redirects_followed += 1;
+              resolved_url = Url::parse(&pointer.to)
+                .map_err(|_| url::ParseError::IdnaError)?;
+            }
+            None => {
+              module_name = resolved_url.to_string();
+              filename = candidate_filename;
+              break;
+            }
+          }
+        }
       }
     }
 
@@ -278,6 +529,11 @@ impl DenoDir {
   }
 }
 
+// NOTE: this adds a leading scheme segment ("http"/"https") to the cache
+// layout so that an `https://` import and an `http://` import to the same
+// host/path no longer collide. Entries written by a pre-scheme-segment
+// version of deno_dir won't be found under the new layout and will simply
+// be treated as a cache miss and re-fetched.
 fn get_cache_filename(basedir: &Path, url: Url) -> PathBuf {
   let host = url.host_str().unwrap();
   let host_port = match url.port() {
@@ -288,6 +544,7 @@ fn get_cache_filename(basedir: &Path, url: Url) -> PathBuf {
   };
 
   let mut out = basedir.to_path_buf();
+  out.push(url.scheme());
   out.push(host_port);
   for path_seg in url.path_segments().unwrap() {
     out.push(path_seg);
@@ -302,7 +559,18 @@ fn test_get_cache_filename() {
   let cache_file = get_cache_filename(&basedir, url);
   assert_eq!(
     cache_file,
-    Path::new("/cache/dir/example.com_PORT1234/path/to/file.ts")
+    Path::new("/cache/dir/http/example.com_PORT1234/path/to/file.ts")
+  );
+}
+
+#[test]
+fn test_get_cache_filename_https() {
+  let url = Url::parse("https://example.com/path/to/file.ts").unwrap();
+  let basedir = Path::new("/cache/dir/");
+  let cache_file = get_cache_filename(&basedir, url);
+  assert_eq!(
+    cache_file,
+    Path::new("/cache/dir/https/example.com/path/to/file.ts")
   );
 }
 
@@ -311,14 +579,145 @@ pub struct CodeFetchOutput {
   pub module_name: String,
   pub filename: String,
   pub source_code: String,
+  // The Content-Type reported by the server on the last fetch or
+  // revalidation of a remote module. None for local files, or for a cached
+  // remote module whose sidecar metadata predates this field.
+  pub maybe_content_type: Option<String>,
   pub maybe_output_code: Option<String>,
 }
 
+// The result of resolving a module's source: its true identity (which may
+// differ from what was requested, if the server redirected) plus the bytes
+// and Content-Type.
+struct ResolvedSource {
+  module_name: String,
+  filename: String,
+  source_code: String,
+  maybe_content_type: Option<String>,
+}
+
+// How many redirect pointer files resolve_module will chase before giving
+// up and treating the current URL as final. Guards against a redirect loop
+// between two cached entries.
+const MAX_REDIRECTS_CHASED: u8 = 10;
+
+// A small pointer file left at `<filename>.redirect` when the server
+// answered a request for `filename`'s URL with a redirect to `to`. Lets
+// `resolve_module` resolve straight to the real cache entry without ever
+// hitting the network again.
+#[derive(Debug, Serialize, Deserialize)]
+struct RedirectPointer {
+  to: String,
+}
+
+impl RedirectPointer {
+  fn pointer_path(filename: &str) -> PathBuf {
+    PathBuf::from(format!("{}.redirect", filename))
+  }
+
+  fn load(filename: &str) -> Option<RedirectPointer> {
+    let contents = fs::read_to_string(Self::pointer_path(filename)).ok()?;
+    serde_json::from_str(&contents).ok()
+  }
+
+  fn save(&self, filename: &str) -> std::io::Result<()> {
+    let serialized = serde_json::to_string_pretty(self)
+      .expect("RedirectPointer should always serialize");
+    let path = Self::pointer_path(filename);
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serialized.as_bytes())
+  }
+}
+
+#[test]
+fn test_redirect_pointer_round_trip() {
+  let (temp_dir, _deno_dir) = test_setup();
+  let filename = temp_dir.path().join("mod.ts");
+  let filename = filename.to_str().unwrap();
+
+  assert!(RedirectPointer::load(filename).is_none());
+
+  let pointer = RedirectPointer {
+    to: "http://example.com/final/mod.ts".to_string(),
+  };
+  pointer.save(filename).expect("save fail");
+
+  let loaded = RedirectPointer::load(filename).expect("load fail");
+  assert_eq!(loaded.to, pointer.to);
+}
+
+// Sidecar metadata stored next to each cached remote resource in `deps`, at
+// `<filename>.headers.json`. Lets `fetch_remote_source` revalidate cheaply
+// (If-None-Match / If-Modified-Since) instead of always re-downloading, and
+// lets downstream code recover the original Content-Type.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CachedHeaders {
+  etag: Option<String>,
+  last_modified: Option<String>,
+  content_type: Option<String>,
+}
+
+impl CachedHeaders {
+  fn headers_path(filename: &str) -> PathBuf {
+    PathBuf::from(format!("{}.headers.json", filename))
+  }
+
+  fn load(filename: &str) -> Option<CachedHeaders> {
+    let contents = fs::read_to_string(Self::headers_path(filename)).ok()?;
+    serde_json::from_str(&contents).ok()
+  }
+
+  fn save(&self, filename: &str) -> std::io::Result<()> {
+    let serialized = serde_json::to_string_pretty(self)
+      .expect("CachedHeaders should always serialize");
+    fs::write(Self::headers_path(filename), serialized.as_bytes())
+  }
+}
+
+impl From<net::Fetched> for CachedHeaders {
+  fn from(fetched: net::Fetched) -> Self {
+    CachedHeaders {
+      etag: fetched.etag,
+      last_modified: fetched.last_modified,
+      content_type: fetched.content_type,
+    }
+  }
+}
+
+#[test]
+fn test_cached_headers_round_trip() {
+  let (temp_dir, _deno_dir) = test_setup();
+  let filename = temp_dir.path().join("mod.ts");
+  let filename = filename.to_str().unwrap();
+
+  assert!(CachedHeaders::load(filename).is_none());
+
+  let headers = CachedHeaders {
+    etag: Some("abc123".to_string()),
+    last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+    content_type: Some("application/typescript".to_string()),
+  };
+  headers.save(filename).expect("save fail");
+
+  let loaded = CachedHeaders::load(filename).expect("load fail");
+  assert_eq!(loaded.etag, headers.etag);
+  assert_eq!(loaded.last_modified, headers.last_modified);
+  assert_eq!(loaded.content_type, headers.content_type);
+}
+
 #[cfg(test)]
 pub fn test_setup() -> (TempDir, DenoDir) {
+  test_setup_with_policy(CachePolicy::UseCache)
+}
+
+#[cfg(test)]
+fn test_setup_with_policy(cache_policy: CachePolicy) -> (TempDir, DenoDir) {
   let temp_dir = TempDir::new().expect("tempdir fail");
   let deno_dir =
-    DenoDir::new(false, Some(temp_dir.path())).expect("setup fail");
+    DenoDir::new(cache_policy, Some(temp_dir.path()), true, None)
+      .expect("setup fail");
   (temp_dir, deno_dir)
 }
 
@@ -326,9 +725,9 @@ pub fn test_setup() -> (TempDir, DenoDir) {
 fn test_cache_path() {
   let (temp_dir, deno_dir) = test_setup();
   assert_eq!(
-    temp_dir
-      .path()
-      .join("gen/a3e29aece8d35a19bf9da2bb1c086af71fb36ed5.js"),
+    temp_dir.path().join(
+      "gen/v2_d0f51fc88a82de05b74b29c87a34d39fd3d5d7950cf307521cbef2dbbd500a29.js"
+    ),
     deno_dir.cache_path("hello.ts", "1+2")
   );
 }
@@ -341,9 +740,9 @@ fn test_code_cache() {
   let source_code = "1+2";
   let output_code = "1+2 // output code";
   let cache_path = deno_dir.cache_path(filename, source_code);
-  assert!(
-    cache_path.ends_with("gen/e8e3ee6bee4aef2ec63f6ec3db7fc5fdfae910ae.js")
-  );
+  assert!(cache_path.ends_with(
+    "gen/v2_40863bb59336b06d7e7cb446b3361937949fab4def38f8bb559fc60f5c011c70.js"
+  ));
 
   let r = deno_dir.code_cache(filename, source_code, output_code);
   r.expect("code_cache error");
@@ -351,15 +750,253 @@ fn test_code_cache() {
   assert_eq!(output_code, fs::read_to_string(&cache_path).unwrap());
 }
 
+#[test]
+fn test_check_integrity_records_on_first_sight() {
+  let (_temp_dir, deno_dir) = test_setup();
+  let module_name = "http://example.com/mod.ts";
+  deno_dir
+    .check_integrity(module_name, b"console.log('hello')")
+    .expect("first sight should record and pass");
+}
+
+#[test]
+fn test_check_integrity_matches_recorded_digest() {
+  let (_temp_dir, deno_dir) = test_setup();
+  let module_name = "http://example.com/mod.ts";
+  deno_dir
+    .check_integrity(module_name, b"console.log('hello')")
+    .unwrap();
+  deno_dir
+    .check_integrity(module_name, b"console.log('hello')")
+    .expect("identical bytes should still pass");
+}
+
+#[test]
+fn test_check_integrity_detects_mismatch() {
+  let (_temp_dir, deno_dir) = test_setup();
+  let module_name = "http://example.com/mod.ts";
+  deno_dir
+    .check_integrity(module_name, b"console.log('hello')")
+    .unwrap();
+  let r = deno_dir.check_integrity(module_name, b"console.log('tampered')");
+  assert!(r.is_err());
+  assert_eq!(r.unwrap_err().kind(), ErrorKind::IntegrityMismatch);
+}
+
+// Bumped whenever the cache-key scheme changes (e.g. the hash algorithm) so
+// that entries written under an old scheme are ignored rather than mis-read
+// -- they simply fall through to a cache miss and get regenerated.
+const CACHE_KEY_VERSION: &str = "v2";
+
+#[test]
+fn test_cached_only_absent_is_an_error() {
+  let (_temp_dir, deno_dir) = test_setup_with_policy(CachePolicy::CachedOnly);
+  let module_name = "http://example.com/mod.ts";
+  let filename = deno_dir
+    .deps
+    .join("http/example.com/mod.ts")
+    .to_str()
+    .unwrap()
+    .to_string();
+
+  let r = deno_dir.fetch_remote_source(module_name, &filename);
+  assert!(r.is_err());
+  assert_eq!(r.unwrap_err().kind(), ErrorKind::CachedOnlyNotFound);
+}
+
+#[test]
+fn test_cached_only_present_reads_from_disk() {
+  let (_temp_dir, deno_dir) = test_setup_with_policy(CachePolicy::CachedOnly);
+  let module_name = "http://example.com/mod.ts";
+  let filename = deno_dir
+    .deps
+    .join("http/example.com/mod.ts")
+    .to_str()
+    .unwrap()
+    .to_string();
+  fs::create_dir_all(Path::new(&filename).parent().unwrap()).unwrap();
+  deno_fs::write_file_sync(Path::new(&filename), b"console.log('hi')")
+    .unwrap();
+
+  let resolved = deno_dir
+    .fetch_remote_source(module_name, &filename)
+    .expect("should read from cache without touching the network");
+  assert_eq!(resolved.source_code, "console.log('hi')");
+}
+
+#[test]
+fn test_use_cache_present_reads_from_disk() {
+  let (_temp_dir, deno_dir) = test_setup_with_policy(CachePolicy::UseCache);
+  let module_name = "http://example.com/mod.ts";
+  let filename = deno_dir
+    .deps
+    .join("http/example.com/mod.ts")
+    .to_str()
+    .unwrap()
+    .to_string();
+  fs::create_dir_all(Path::new(&filename).parent().unwrap()).unwrap();
+  deno_fs::write_file_sync(Path::new(&filename), b"console.log('hi')")
+    .unwrap();
+
+  let resolved = deno_dir
+    .fetch_remote_source(module_name, &filename)
+    .expect("should read from cache without touching the network");
+  assert_eq!(resolved.source_code, "console.log('hi')");
+}
+
+#[test]
+fn test_reload_revalidates_with_cached_headers() {
+  // CachePolicy::Reload must still send the previously cached etag and
+  // Last-Modified, so a 304 can keep the on-disk body instead of
+  // re-downloading it. fetch_remote_source_with lets us assert that
+  // without a real network call.
+  let (_temp_dir, deno_dir) = test_setup_with_policy(CachePolicy::Reload);
+  let module_name = "http://example.com/mod.ts";
+  let filename = deno_dir
+    .deps
+    .join("http/example.com/mod.ts")
+    .to_str()
+    .unwrap()
+    .to_string();
+  fs::create_dir_all(Path::new(&filename).parent().unwrap()).unwrap();
+  deno_fs::write_file_sync(Path::new(&filename), b"console.log('stale? no')")
+    .unwrap();
+  CachedHeaders {
+    etag: Some("abc123".to_string()),
+    last_modified: Some("Tue, 01 Jan 2019 00:00:00 GMT".to_string()),
+    content_type: Some("application/javascript".to_string()),
+  }.save(&filename)
+    .unwrap();
+
+  let resolved = deno_dir
+    .fetch_remote_source_with(module_name, &filename, |_, etag, last_modified| {
+      assert_eq!(etag, Some("abc123"));
+      assert_eq!(last_modified, Some("Tue, 01 Jan 2019 00:00:00 GMT"));
+      Ok(FetchResult::NotModified)
+    })
+    .expect("should keep the cached copy on a 304");
+  assert_eq!(resolved.source_code, "console.log('stale? no')");
+  assert_eq!(
+    resolved.maybe_content_type,
+    Some("application/javascript".to_string())
+  );
+}
+
+#[test]
+fn test_reload_with_fresh_response_rewrites_cache() {
+  // When the revalidation comes back Fresh (not a 304), Reload must persist
+  // the new body and headers over whatever was cached before.
+  let (_temp_dir, deno_dir) = test_setup_with_policy(CachePolicy::Reload);
+  let module_name = "http://example.com/mod.ts";
+  let filename = deno_dir
+    .deps
+    .join("http/example.com/mod.ts")
+    .to_str()
+    .unwrap()
+    .to_string();
+  fs::create_dir_all(Path::new(&filename).parent().unwrap()).unwrap();
+  deno_fs::write_file_sync(Path::new(&filename), b"console.log('old')")
+    .unwrap();
+
+  let resolved = deno_dir
+    .fetch_remote_source_with(module_name, &filename, |name, _, _| {
+      Ok(FetchResult::Fresh(net::Fetched {
+        body: "console.log('new')".to_string(),
+        etag: Some("new-etag".to_string()),
+        last_modified: None,
+        content_type: Some("application/javascript".to_string()),
+        final_url: name.to_string(),
+      }))
+    }).expect("should accept the fresh response");
+  assert_eq!(resolved.source_code, "console.log('new')");
+  assert_eq!(
+    fs::read_to_string(&filename).unwrap(),
+    "console.log('new')"
+  );
+  assert_eq!(
+    CachedHeaders::load(&filename).unwrap().etag,
+    Some("new-etag".to_string())
+  );
+}
+
+#[test]
+fn test_use_cache_absent_downloads() {
+  let (_temp_dir, deno_dir) = test_setup_with_policy(CachePolicy::UseCache);
+  let module_name = "http://example.com/mod.ts";
+  let filename = deno_dir
+    .deps
+    .join("http/example.com/mod.ts")
+    .to_str()
+    .unwrap()
+    .to_string();
+
+  let resolved = deno_dir
+    .fetch_remote_source_with(module_name, &filename, |name, etag, last_modified| {
+      assert_eq!(etag, None);
+      assert_eq!(last_modified, None);
+      Ok(FetchResult::Fresh(net::Fetched {
+        body: "console.log('downloaded')".to_string(),
+        etag: Some("fresh-etag".to_string()),
+        last_modified: None,
+        content_type: Some("application/javascript".to_string()),
+        final_url: name.to_string(),
+      }))
+    }).expect("should download and cache the missing module");
+  assert_eq!(resolved.source_code, "console.log('downloaded')");
+  assert_eq!(
+    fs::read_to_string(&filename).unwrap(),
+    "console.log('downloaded')"
+  );
+}
+
+#[test]
+fn test_reload_absent_downloads() {
+  let (_temp_dir, deno_dir) = test_setup_with_policy(CachePolicy::Reload);
+  let module_name = "http://example.com/mod.ts";
+  let filename = deno_dir
+    .deps
+    .join("http/example.com/mod.ts")
+    .to_str()
+    .unwrap()
+    .to_string();
+
+  let resolved = deno_dir
+    .fetch_remote_source_with(module_name, &filename, |name, etag, last_modified| {
+      // No cached file exists yet, so there's nothing to revalidate with;
+      // Reload falls into the same no-conditional-headers download path as
+      // UseCache does for an absent file.
+      assert_eq!(etag, None);
+      assert_eq!(last_modified, None);
+      Ok(FetchResult::Fresh(net::Fetched {
+        body: "console.log('downloaded')".to_string(),
+        etag: Some("fresh-etag".to_string()),
+        last_modified: None,
+        content_type: Some("application/javascript".to_string()),
+        final_url: name.to_string(),
+      }))
+    }).expect("should download and cache the missing module");
+  assert_eq!(resolved.source_code, "console.log('downloaded')");
+  assert_eq!(
+    fs::read_to_string(&filename).unwrap(),
+    "console.log('downloaded')"
+  );
+}
+
 // https://github.com/denoland/deno/blob/golang/deno_dir.go#L25-L30
 fn source_code_hash(filename: &str, source_code: &str) -> String {
-  let mut ctx = ring::digest::Context::new(&ring::digest::SHA1);
+  // SHA-1 is deprecated for collision resistance; this is a cache key, not
+  // a security boundary, but there's no reason to keep using it.
+  let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
   ctx.update(filename.as_bytes());
   ctx.update(source_code.as_bytes());
   let digest = ctx.finish();
+  hex_encode(digest.as_ref())
+}
+
+// TODO There must be a better way to do this...
+fn hex_encode(bytes: &[u8]) -> String {
   let mut out = String::new();
-  // TODO There must be a better way to do this...
-  for byte in digest.as_ref() {
+  for byte in bytes {
     write!(&mut out, "{:02x}", byte).unwrap();
   }
   out
@@ -368,17 +1005,17 @@ fn source_code_hash(filename: &str, source_code: &str) -> String {
 #[test]
 fn test_source_code_hash() {
   assert_eq!(
-    "a3e29aece8d35a19bf9da2bb1c086af71fb36ed5",
+    "d0f51fc88a82de05b74b29c87a34d39fd3d5d7950cf307521cbef2dbbd500a29",
     source_code_hash("hello.ts", "1+2")
   );
   // Different source_code should result in different hash.
   assert_eq!(
-    "914352911fc9c85170908ede3df1128d690dda41",
+    "8ea169cc13f819e893596c450e1e028a1f517ad1a1b45e3af58a2a7cefeb81be",
     source_code_hash("hello.ts", "1")
   );
   // Different filename should result in different hash.
   assert_eq!(
-    "2e396bc66101ecc642db27507048376d972b1b70",
+    "f6253ccb441d1eba080282bd20fb730ae8a0e63efe5baaecde584c4bb1805812",
     source_code_hash("hi.ts", "1+2")
   );
 }
@@ -424,7 +1061,7 @@ fn test_src_file_to_url_1() {
   let (_temp_dir, deno_dir) = test_setup();
   assert_eq!("hello", deno_dir.src_file_to_url("hello"));
   assert_eq!("/hello", deno_dir.src_file_to_url("/hello"));
-  let x = deno_dir.deps.join("hello/world.txt");
+  let x = deno_dir.deps.join("http/hello/world.txt");
   assert_eq!(
     "http://hello/world.txt",
     deno_dir.src_file_to_url(x.to_str().unwrap())
@@ -434,13 +1071,23 @@ fn test_src_file_to_url_1() {
 #[test]
 fn test_src_file_to_url_2() {
   let (_temp_dir, deno_dir) = test_setup();
-  let x = deno_dir.deps.join("localhost_PORT4545/world.txt");
+  let x = deno_dir.deps.join("http/localhost_PORT4545/world.txt");
   assert_eq!(
     "http://localhost:4545/world.txt",
     deno_dir.src_file_to_url(x.to_str().unwrap())
   );
 }
 
+#[test]
+fn test_src_file_to_url_preserves_https() {
+  let (_temp_dir, deno_dir) = test_setup();
+  let x = deno_dir.deps.join("https/hello/world.txt");
+  assert_eq!(
+    "https://hello/world.txt",
+    deno_dir.src_file_to_url(x.to_str().unwrap())
+  );
+}
+
 // https://github.com/denoland/deno/blob/golang/os_test.go#L16-L87
 #[test]
 fn test_resolve_module_1() {
@@ -501,7 +1148,7 @@ fn test_resolve_module_2() {
   let expected_filename = deno_fs::normalize_path(
     deno_dir
       .deps
-      .join("localhost_PORT4545/testdata/subdir/print_hello.ts")
+      .join("http/localhost_PORT4545/testdata/subdir/print_hello.ts")
       .as_ref(),
   );
 
@@ -517,7 +1164,7 @@ fn test_resolve_module_3() {
   let (_temp_dir, deno_dir) = test_setup();
 
   let module_specifier_ =
-    deno_dir.deps.join("unpkg.com/liltest@0.0.5/index.ts");
+    deno_dir.deps.join("http/unpkg.com/liltest@0.0.5/index.ts");
   let module_specifier = module_specifier_.to_str().unwrap();
   let containing_file = ".";
 
@@ -525,7 +1172,7 @@ fn test_resolve_module_3() {
   let expected_filename = deno_fs::normalize_path(
     deno_dir
       .deps
-      .join("unpkg.com/liltest@0.0.5/index.ts")
+      .join("http/unpkg.com/liltest@0.0.5/index.ts")
       .as_ref(),
   );
 
@@ -541,12 +1188,16 @@ fn test_resolve_module_4() {
   let (_temp_dir, deno_dir) = test_setup();
 
   let module_specifier = "./util";
-  let containing_file_ = deno_dir.deps.join("unpkg.com/liltest@0.0.5/index.ts");
+  let containing_file_ =
+    deno_dir.deps.join("http/unpkg.com/liltest@0.0.5/index.ts");
   let containing_file = containing_file_.to_str().unwrap();
 
   let expected_module_name = "http://unpkg.com/liltest@0.0.5/util";
   let expected_filename = deno_fs::normalize_path(
-    deno_dir.deps.join("unpkg.com/liltest@0.0.5/util").as_ref(),
+    deno_dir
+      .deps
+      .join("http/unpkg.com/liltest@0.0.5/util")
+      .as_ref(),
   );
 
   let (module_name, filename) = deno_dir
@@ -566,7 +1217,28 @@ fn test_resolve_module_5() {
   let expected_filename = deno_fs::normalize_path(
     deno_dir
       .deps
-      .join("localhost_PORT4545/tests/subdir/mod2.ts")
+      .join("http/localhost_PORT4545/tests/subdir/mod2.ts")
+      .as_ref(),
+  );
+
+  let (module_name, filename) = deno_dir
+    .resolve_module(module_specifier, containing_file)
+    .unwrap();
+  assert_eq!(module_name, expected_module_name);
+  assert_eq!(filename, expected_filename);
+}
+
+#[test]
+fn test_resolve_module_preserves_https_scheme() {
+  let (_temp_dir, deno_dir) = test_setup();
+
+  let module_specifier = "https://unpkg.com/liltest@0.0.5/index.ts";
+  let containing_file = ".";
+  let expected_module_name = "https://unpkg.com/liltest@0.0.5/index.ts";
+  let expected_filename = deno_fs::normalize_path(
+    deno_dir
+      .deps
+      .join("https/unpkg.com/liltest@0.0.5/index.ts")
       .as_ref(),
   );
 
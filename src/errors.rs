@@ -0,0 +1,72 @@
+// Copyright 2018 the Deno authors. All rights reserved. MIT license.
+use std;
+use std::fmt;
+use url;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorKind {
+  NotFound,
+  PermissionDenied,
+  ConnectionRefused,
+  InvalidInput,
+  InvalidData,
+  UrlParse,
+  IntegrityMismatch,
+  // A remote module was requested under CachePolicy::CachedOnly but isn't
+  // present in the local cache, so it can't be resolved without a network
+  // fetch. Distinct from NotFound so callers can tell "really doesn't
+  // exist" apart from "exists, but we were told not to go fetch it".
+  CachedOnlyNotFound,
+  Other,
+}
+
+#[derive(Debug)]
+pub struct DenoError {
+  kind: ErrorKind,
+  msg: String,
+}
+
+impl DenoError {
+  pub fn new(kind: ErrorKind, msg: String) -> Self {
+    DenoError { kind, msg }
+  }
+
+  pub fn kind(&self) -> ErrorKind {
+    self.kind
+  }
+}
+
+impl fmt::Display for DenoError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.msg)
+  }
+}
+
+impl std::error::Error for DenoError {
+  fn description(&self) -> &str {
+    &self.msg
+  }
+}
+
+impl From<std::io::Error> for DenoError {
+  fn from(err: std::io::Error) -> Self {
+    let kind = match err.kind() {
+      std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+      std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+      std::io::ErrorKind::ConnectionRefused => ErrorKind::ConnectionRefused,
+      std::io::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+      std::io::ErrorKind::InvalidData => ErrorKind::InvalidData,
+      _ => ErrorKind::Other,
+    };
+    let msg = err.to_string();
+    DenoError::new(kind, msg)
+  }
+}
+
+impl From<url::ParseError> for DenoError {
+  fn from(err: url::ParseError) -> Self {
+    DenoError::new(ErrorKind::UrlParse, err.to_string())
+  }
+}
+
+pub type DenoResult<T> = std::result::Result<T, DenoError>;
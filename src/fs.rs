@@ -0,0 +1,31 @@
+// Copyright 2018 the Deno authors. All rights reserved. MIT license.
+use std::fs;
+use std::io;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+pub fn mkdir(path: &Path) -> io::Result<()> {
+  debug!("mkdir -p {}", path.display());
+  fs::create_dir_all(path)
+}
+
+pub fn write_file_sync(filename: &Path, data: &[u8]) -> io::Result<()> {
+  fs::write(filename, data)
+}
+
+// Similar to node's path.normalize(), but for a PathBuf. Resolves "." and
+// ".." components without touching the filesystem.
+pub fn normalize_path(path: &Path) -> String {
+  let mut out = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::ParentDir => {
+        out.pop();
+      }
+      Component::CurDir => {}
+      c => out.push(c.as_os_str()),
+    }
+  }
+  out.to_str().unwrap().to_string()
+}
@@ -0,0 +1,131 @@
+// Copyright 2018 the Deno authors. All rights reserved. MIT license.
+use std::collections::HashMap;
+
+use serde_json;
+
+// A (deliberately simplified) implementation of the import maps proposal:
+// https://github.com/WICG/import-maps
+//
+// Lets `resolve_module` rewrite a bare specifier like "lodash" into
+// something resolvable (a URL or a relative path) before the normal
+// local/remote resolution logic runs.
+#[derive(Debug, Default)]
+pub struct ImportMap {
+  imports: HashMap<String, String>,
+  scopes: HashMap<String, HashMap<String, String>>,
+}
+
+#[derive(Deserialize)]
+struct RawImportMap {
+  #[serde(default)]
+  imports: HashMap<String, String>,
+  #[serde(default)]
+  scopes: HashMap<String, HashMap<String, String>>,
+}
+
+impl ImportMap {
+  pub fn from_json(json_str: &str) -> serde_json::Result<Self> {
+    let raw: RawImportMap = serde_json::from_str(json_str)?;
+    Ok(ImportMap {
+      imports: raw.imports,
+      scopes: raw.scopes,
+    })
+  }
+
+  // Attempts to rewrite `specifier` as it would be imported from
+  // `containing_file`. Returns None if no entry applies, in which case the
+  // caller should fall through to its current behavior.
+  pub fn resolve(
+    &self,
+    specifier: &str,
+    containing_file: &str,
+  ) -> Option<String> {
+    if let Some(scope) = self.matching_scope(containing_file) {
+      if let Some(resolved) = resolve_in(&self.scopes[scope], specifier) {
+        return Some(resolved);
+      }
+    }
+    resolve_in(&self.imports, specifier)
+  }
+
+  // The longest scope key that is a prefix of `containing_file`, per the
+  // import maps spec's scope-matching algorithm.
+  fn matching_scope(&self, containing_file: &str) -> Option<&str> {
+    self
+      .scopes
+      .keys()
+      .filter(|scope| containing_file.starts_with(scope.as_str()))
+      .max_by_key(|scope| scope.len())
+      .map(|scope| scope.as_str())
+  }
+}
+
+// Exact match wins; otherwise the longest trailing-slash prefix key whose
+// prefix matches `specifier` is substituted in.
+fn resolve_in(
+  map: &HashMap<String, String>,
+  specifier: &str,
+) -> Option<String> {
+  if let Some(target) = map.get(specifier) {
+    return Some(target.clone());
+  }
+  map
+    .iter()
+    .filter(|(key, _)| key.ends_with('/') && specifier.starts_with(key.as_str()))
+    .max_by_key(|(key, _)| key.len())
+    .map(|(key, target)| format!("{}{}", target, &specifier[key.len()..]))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn test_map() -> ImportMap {
+    ImportMap::from_json(
+      r#"{
+        "imports": {
+          "lodash": "https://cdn.example.com/lodash@4.17.11/index.js",
+          "utils/": "./shared/utils/"
+        },
+        "scopes": {
+          "/project/vendor/": {
+            "utils/": "./vendor/utils/"
+          }
+        }
+      }"#,
+    ).unwrap()
+  }
+
+  #[test]
+  fn test_exact_key() {
+    let map = test_map();
+    assert_eq!(
+      map.resolve("lodash", "/project/main.ts"),
+      Some("https://cdn.example.com/lodash@4.17.11/index.js".to_string())
+    );
+  }
+
+  #[test]
+  fn test_trailing_slash_prefix() {
+    let map = test_map();
+    assert_eq!(
+      map.resolve("utils/format.ts", "/project/main.ts"),
+      Some("./shared/utils/format.ts".to_string())
+    );
+  }
+
+  #[test]
+  fn test_scope_precedence() {
+    let map = test_map();
+    assert_eq!(
+      map.resolve("utils/format.ts", "/project/vendor/pkg/index.ts"),
+      Some("./vendor/utils/format.ts".to_string())
+    );
+  }
+
+  #[test]
+  fn test_fall_through_when_no_match() {
+    let map = test_map();
+    assert_eq!(map.resolve("./relative.ts", "/project/main.ts"), None);
+  }
+}
@@ -0,0 +1,186 @@
+// Copyright 2018 the Deno authors. All rights reserved. MIT license.
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use base64;
+use ring;
+use serde_json;
+
+// Maps a resolved remote module's name (its URL) to a "sha256-<base64>"
+// digest of the bytes it held the last time it was fetched or verified.
+// Persisted as JSON at `DenoDir::root.join("deno.lock")`, analogous to
+// npm's package-lock.json or Cargo.lock: it lets a second run detect that a
+// remote dependency changed underneath it.
+#[derive(Debug)]
+pub struct Lockfile {
+  path: PathBuf,
+  entries: HashMap<String, String>,
+}
+
+impl Lockfile {
+  // A missing lockfile just means "no entries yet" (e.g. first run), but a
+  // *present-and-unparseable* one -- e.g. truncated by a crash, or read
+  // mid-write by a concurrent `deno` process -- must not be treated the
+  // same way: silently falling back to an empty map would make every
+  // module look unseen and get re-recorded with whatever bytes are
+  // currently on disk, defeating the point of the integrity check.
+  pub fn new(path: PathBuf) -> std::io::Result<Self> {
+    let entries = match fs::read_to_string(&path) {
+      Ok(contents) => serde_json::from_str(&contents).map_err(|e| {
+        std::io::Error::new(
+          std::io::ErrorKind::InvalidData,
+          format!("lockfile at {} is corrupt: {}", path.display(), e),
+        )
+      })?,
+      Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+        HashMap::new()
+      }
+      Err(e) => return Err(e),
+    };
+    Ok(Lockfile { path, entries })
+  }
+
+  pub fn write(&self) -> std::io::Result<()> {
+    let serialized = serde_json::to_string_pretty(&self.entries)
+      .expect("lockfile entries should always serialize");
+    // Write to a uniquely-named temp file beside the lockfile first, then
+    // rename into place. `fs::rename` is atomic on the same filesystem, so
+    // a reader (including a concurrently-running `deno` process) never
+    // observes a torn write, the same pattern `code_cache` uses for the
+    // compiled-output cache.
+    let temp_path = self.path.with_file_name(format!(
+      "{}.{}.tmp",
+      self.path.file_name().unwrap().to_str().unwrap(),
+      std::process::id()
+    ));
+    fs::write(&temp_path, serialized.as_bytes())?;
+    fs::rename(&temp_path, &self.path)
+  }
+
+  // Checks `bytes` against the digest recorded for `module_name`.
+  //
+  // - If `module_name` has no entry yet: when `lock_write` is true, records
+  //   the digest and returns Ok; otherwise returns Err (enforcing mode
+  //   refuses to trust unseen modules).
+  // - If `module_name` has an entry: returns Ok when the digest matches, Err
+  //   with (expected, actual) otherwise.
+  pub fn check_or_insert(
+    &mut self,
+    module_name: &str,
+    bytes: &[u8],
+    lock_write: bool,
+  ) -> Result<(), (String, String)> {
+    let actual = digest(bytes);
+    match self.entries.get(module_name).cloned() {
+      Some(expected) => {
+        if expected == actual {
+          Ok(())
+        } else {
+          Err((expected, actual))
+        }
+      }
+      None => {
+        if lock_write {
+          self.entries.insert(module_name.to_string(), actual);
+          Ok(())
+        } else {
+          Err(("<no entry>".to_string(), actual))
+        }
+      }
+    }
+  }
+}
+
+pub fn digest(bytes: &[u8]) -> String {
+  let actual = ring::digest::digest(&ring::digest::SHA256, bytes);
+  format!("sha256-{}", base64::encode(actual.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  fn new_lockfile() -> (TempDir, Lockfile) {
+    let temp_dir = TempDir::new().expect("tempdir fail");
+    let path = temp_dir.path().join("deno.lock");
+    let lockfile = Lockfile::new(path).expect("lockfile new fail");
+    (temp_dir, lockfile)
+  }
+
+  #[test]
+  fn test_first_fetch_records_digest() {
+    let (_temp_dir, mut lockfile) = new_lockfile();
+    let r =
+      lockfile.check_or_insert("http://example.com/a.ts", b"hello", true);
+    assert!(r.is_ok());
+    assert_eq!(
+      lockfile.entries.get("http://example.com/a.ts").unwrap(),
+      &digest(b"hello")
+    );
+  }
+
+  #[test]
+  fn test_matching_digest_ok() {
+    let (_temp_dir, mut lockfile) = new_lockfile();
+    lockfile
+      .check_or_insert("http://example.com/a.ts", b"hello", true)
+      .unwrap();
+    let r =
+      lockfile.check_or_insert("http://example.com/a.ts", b"hello", false);
+    assert!(r.is_ok());
+  }
+
+  #[test]
+  fn test_mismatched_digest_err() {
+    let (_temp_dir, mut lockfile) = new_lockfile();
+    lockfile
+      .check_or_insert("http://example.com/a.ts", b"hello", true)
+      .unwrap();
+    let r = lockfile.check_or_insert(
+      "http://example.com/a.ts",
+      b"goodbye",
+      false,
+    );
+    assert!(r.is_err());
+  }
+
+  #[test]
+  fn test_unseen_entry_without_lock_write_err() {
+    let (_temp_dir, mut lockfile) = new_lockfile();
+    let r =
+      lockfile.check_or_insert("http://example.com/a.ts", b"hello", false);
+    assert!(r.is_err());
+  }
+
+  #[test]
+  fn test_write_round_trip() {
+    let (_temp_dir, mut lockfile) = new_lockfile();
+    lockfile
+      .check_or_insert("http://example.com/a.ts", b"hello", true)
+      .unwrap();
+    lockfile.write().expect("write fail");
+    let reloaded =
+      Lockfile::new(lockfile.path.clone()).expect("lockfile new fail");
+    let r = reloaded.entries.get("http://example.com/a.ts");
+    assert_eq!(r, Some(&digest(b"hello")));
+  }
+
+  #[test]
+  fn test_corrupt_file_is_an_error() {
+    let temp_dir = TempDir::new().expect("tempdir fail");
+    let path = temp_dir.path().join("deno.lock");
+    fs::write(&path, b"not valid json").expect("write fail");
+    let r = Lockfile::new(path);
+    assert!(r.is_err());
+  }
+
+  #[test]
+  fn test_missing_file_is_empty() {
+    let temp_dir = TempDir::new().expect("tempdir fail");
+    let path = temp_dir.path().join("deno.lock");
+    let lockfile = Lockfile::new(path).expect("lockfile new fail");
+    assert!(lockfile.entries.is_empty());
+  }
+}
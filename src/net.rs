@@ -0,0 +1,104 @@
+// Copyright 2018 the Deno authors. All rights reserved. MIT license.
+use errors::DenoError;
+use errors::DenoResult;
+use errors::ErrorKind;
+use reqwest;
+use reqwest::header;
+
+// TODO(ry) This is not really async, it blocks the calling thread. It should
+// be rewritten in terms of the tokio reactor once the rest of the runtime is
+// async.
+pub fn fetch_sync_string(module_name: &str) -> DenoResult<String> {
+  match fetch_sync_string_conditional(module_name, None, None)? {
+    FetchResult::Fresh(fetched) => Ok(fetched.body),
+    // No conditional headers were sent, so a well-behaved server should
+    // never answer 304 here -- but the server isn't trusted input, so
+    // treat a misbehaving one as a recoverable error, not a panic.
+    FetchResult::NotModified => Err(DenoError::new(
+      ErrorKind::Other,
+      format!(
+        "{} returned 304 Not Modified to a request with no conditional headers",
+        module_name
+      ),
+    )),
+  }
+}
+
+#[derive(Debug)]
+pub struct Fetched {
+  pub body: String,
+  pub etag: Option<String>,
+  pub last_modified: Option<String>,
+  pub content_type: Option<String>,
+  // The URL the response actually came from, after following any redirects.
+  // Equal to the requested URL unless the server redirected.
+  pub final_url: String,
+}
+
+#[derive(Debug)]
+pub enum FetchResult {
+  // Server answered 304 Not Modified: the caller's cached copy is still
+  // valid.
+  NotModified,
+  Fresh(Fetched),
+}
+
+// Fetches `module_name`, sending `If-None-Match`/`If-Modified-Since` when
+// the caller has previously cached values for them. Used by
+// `DenoDir::fetch_remote_source` to revalidate cheaply instead of always
+// re-downloading.
+pub fn fetch_sync_string_conditional(
+  module_name: &str,
+  etag: Option<&str>,
+  last_modified: Option<&str>,
+) -> DenoResult<FetchResult> {
+  let client = reqwest::Client::new();
+  let mut req = client.get(module_name);
+  if let Some(etag) = etag {
+    req = req.header(header::IF_NONE_MATCH, etag);
+  }
+  if let Some(last_modified) = last_modified {
+    req = req.header(header::IF_MODIFIED_SINCE, last_modified);
+  }
+
+  let mut response = req
+    .send()
+    .map_err(|e| DenoError::new(ErrorKind::ConnectionRefused, e.to_string()))?;
+
+  if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+    return Ok(FetchResult::NotModified);
+  }
+
+  if !response.status().is_success() {
+    return Err(DenoError::new(
+      ErrorKind::NotFound,
+      format!("{} returned {}", module_name, response.status()),
+    ));
+  }
+
+  let header_str = |name: header::HeaderName| -> Option<String> {
+    response
+      .headers()
+      .get(name)
+      .and_then(|v| v.to_str().ok())
+      .map(|s| s.to_string())
+  };
+  let etag = header_str(header::ETAG);
+  let last_modified = header_str(header::LAST_MODIFIED);
+  let content_type = header_str(header::CONTENT_TYPE);
+  // reqwest's Client follows redirects transparently, so by the time we get
+  // here `response.url()` already reflects the final, post-redirect URL.
+  let final_url = response.url().to_string();
+
+  let body = response
+    .text()
+    .map_err(|e| DenoError::new(ErrorKind::Other, e.to_string()))?;
+
+  Ok(FetchResult::Fresh(Fetched {
+    body,
+    etag,
+    last_modified,
+    content_type,
+    final_url,
+  }))
+}